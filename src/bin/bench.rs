@@ -10,6 +10,8 @@ use std::time::Duration;
 
 use crossbeam::scope;
 use crossbeam::sync::MsQueue;
+use crossbeam::sync::ArrayQueue;
+use crossbeam::sync::channel as bounded_channel;
 
 use extra_impls::mpsc_queue::Queue as MpscQueue;
 
@@ -52,6 +54,22 @@ impl<T> Queue<T> for Mutex<VecDeque<T>> {
     fn pop(&self) -> Option<T> { self.lock().unwrap().pop_front() }
 }
 
+impl<T> Queue<T> for ArrayQueue<T> {
+    fn push(&self, t: T) {
+        // The trait has no room for backpressure, so just retry until a
+        // slot frees up; real callers of a bounded queue handle `push`'s
+        // `Err` themselves.
+        let mut value = t;
+        loop {
+            match ArrayQueue::push(self, value) {
+                Ok(()) => return,
+                Err(returned) => value = returned,
+            }
+        }
+    }
+    fn pop(&self) -> Option<T> { ArrayQueue::pop(self) }
+}
+
 fn bench_queue_mpsc<Q: Queue<u64> + Sync>(q: Q) -> f64 {
     let d = Duration::span(|| {
         scope(|scope| {
@@ -100,11 +118,34 @@ fn bench_chan_mpsc() -> f64 {
     nanos(d) / ((COUNT * THREADS) as f64)
 }
 
-fn bench_queue_mpmc() -> f64 {
+fn bench_bounded_chan_mpsc() -> f64 {
+    let (tx, rx) = bounded_channel(1024);
+
+    let d = Duration::span(|| {
+        scope(|scope| {
+            for _i in 0..THREADS {
+                let my_tx = tx.clone();
+
+                scope.spawn(move || {
+                    for x in 0..COUNT {
+                        let _ = my_tx.send(x);
+                    }
+                });
+            }
+
+            for _i in 0..COUNT*THREADS {
+                let _ = rx.recv().unwrap();
+            }
+        });
+    });
+
+    nanos(d) / ((COUNT * THREADS) as f64)
+}
+
+fn bench_queue_mpmc<Q: Queue<bool> + Sync>(q: Q) -> f64 {
     use std::sync::atomic::AtomicUsize;
     use std::sync::atomic::Ordering::Relaxed;
 
-    let q = MsQueue::new();
     let prod_count = AtomicUsize::new(0);
 
     let d = Duration::span(|| {
@@ -175,8 +216,10 @@ fn bench_mutex_mpmc() -> f64 {
 fn main() {
     println!("MSQ mpsc: {}", bench_queue_mpsc(MsQueue::new()));
     println!("chan mpsc: {}", bench_chan_mpsc());
+    println!("bounded chan mpsc: {}", bench_bounded_chan_mpsc());
     println!("mpsc mpsc: {}", bench_queue_mpsc(MpscQueue::new()));
+    println!("msq mpmc: {}", bench_queue_mpmc(MsQueue::new()));
+    println!("array mpmc: {}", bench_queue_mpmc(ArrayQueue::with_capacity(1024)));
 //    println!("queue_mpsc: {}", bench_queue_mpsc());
-//    println!("queue_mpmc: {}", bench_queue_mpmc());
 //   println!("mutex_mpmc: {}", bench_mutex_mpmc());
 }
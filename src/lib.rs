@@ -0,0 +1,14 @@
+//! Support for concurrent and parallel programming.
+//!
+//! This crate is an early work in progress. The focus for the moment is
+//! concurrent data structures and a scoped API for spawning threads that
+//! can safely access data on the stack of the spawning thread.
+
+pub use scoped::{scope, Scope, ScopedJoinHandle};
+pub use pool::{Pool, PoolScope};
+
+mod scoped;
+mod pool;
+
+pub mod mem;
+pub mod sync;
@@ -0,0 +1,164 @@
+//! A lightweight epoch-based memory reclamation scheme.
+//!
+//! Lock-free data structures frequently need to free a node after
+//! unlinking it, but another thread might still be in the middle of
+//! reading it. Epoch-based reclamation defers the actual `free` until
+//! every thread that could have observed the node has since quiesced.
+//!
+//! This is a deliberately small implementation: there is no epoch counter,
+//! just one global garbage bag and a count of currently pinned threads.
+//! Everything in the bag is freed whenever the pinned count drops to zero.
+//! It is enough to make `MsQueue` and friends safe without requiring a
+//! full hazard-pointer or per-thread epoch registry.
+
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static PINNED: AtomicUsize = AtomicUsize::new(0);
+static GARBAGE: Mutex<Vec<Box<dyn FnOnce() + Send>>> = Mutex::new(Vec::new());
+
+/// An atomic, nullable pointer into the epoch-managed heap.
+pub struct Atomic<T> {
+    ptr: ::std::sync::atomic::AtomicPtr<T>,
+    _marker: PhantomData<*mut T>,
+}
+
+unsafe impl<T: Send> Send for Atomic<T> {}
+unsafe impl<T: Send> Sync for Atomic<T> {}
+
+/// An owned, not-yet-shared heap allocation.
+pub struct Owned<T> {
+    data: Box<T>,
+}
+
+/// A shared, epoch-protected reference into the heap.
+pub struct Shared<'a, T: 'a> {
+    data: *mut T,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<T> Owned<T> {
+    pub fn new(t: T) -> Owned<T> {
+        Owned { data: Box::new(t) }
+    }
+
+    fn into_raw(self) -> *mut T {
+        Box::into_raw(self.data)
+    }
+}
+
+impl<T> ::std::ops::Deref for Owned<T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.data }
+}
+
+impl<T> ::std::ops::DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T { &mut self.data }
+}
+
+impl<'a, T> Shared<'a, T> {
+    unsafe fn from_raw(data: *mut T) -> Option<Shared<'a, T>> {
+        if data.is_null() { None } else { Some(Shared { data, _marker: PhantomData }) }
+    }
+
+    pub fn as_raw(&self) -> *mut T { self.data }
+}
+
+impl<'a, T> Clone for Shared<'a, T> {
+    fn clone(&self) -> Shared<'a, T> { *self }
+}
+impl<'a, T> Copy for Shared<'a, T> {}
+
+impl<'a, T> ::std::ops::Deref for Shared<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.data } }
+}
+
+impl<T> Atomic<T> {
+    pub fn null() -> Atomic<T> {
+        Atomic { ptr: ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut()), _marker: PhantomData }
+    }
+
+    pub fn new(t: T) -> Atomic<T> {
+        let a = Atomic::null();
+        a.ptr.store(Owned::new(t).into_raw(), Ordering::Relaxed);
+        a
+    }
+
+    pub fn load<'a>(&self, ord: Ordering, _guard: &'a Guard) -> Option<Shared<'a, T>> {
+        unsafe { Shared::from_raw(self.ptr.load(ord)) }
+    }
+
+    pub fn store(&self, val: Option<Owned<T>>, ord: Ordering) {
+        let ptr = val.map(|o| o.into_raw()).unwrap_or(::std::ptr::null_mut());
+        self.ptr.store(ptr, ord);
+    }
+
+    /// Store an already-shared pointer, without taking new ownership.
+    pub fn store_shared(&self, val: Option<Shared<T>>, ord: Ordering) {
+        let ptr = val.map(|s| s.as_raw()).unwrap_or(::std::ptr::null_mut());
+        self.ptr.store(ptr, ord);
+    }
+
+    /// Compare-and-swap the pointer, taking ownership of `new` on success
+    /// and handing `new` back unharmed on failure.
+    pub fn cas(&self, old: Option<Shared<T>>, new: Option<Owned<T>>, ord: Ordering) -> Result<(), Option<Owned<T>>> {
+        let old_raw = old.map(|s| s.as_raw()).unwrap_or(::std::ptr::null_mut());
+        let new_raw = new.as_ref().map(|o| &**o as *const T as *mut T).unwrap_or(::std::ptr::null_mut());
+        match self.ptr.compare_exchange(old_raw, new_raw, ord, Ordering::Relaxed) {
+            Ok(_) => { if let Some(o) = new { mem::forget(o); } Ok(()) }
+            Err(_) => Err(new),
+        }
+    }
+
+    /// Compare-and-swap using an already-shared pointer for `new`.
+    pub fn cas_shared(&self, old: Option<Shared<T>>, new: Option<Shared<T>>, ord: Ordering) -> bool {
+        let old_raw = old.map(|s| s.as_raw()).unwrap_or(::std::ptr::null_mut());
+        let new_raw = new.map(|s| s.as_raw()).unwrap_or(::std::ptr::null_mut());
+        self.ptr.compare_exchange(old_raw, new_raw, ord, Ordering::Relaxed).is_ok()
+    }
+}
+
+/// A proof that the current thread is pinned, allowing loads from
+/// `Atomic<T>` and the scheduling of deferred frees.
+pub struct Guard {
+    _private: (),
+}
+
+impl Guard {
+    /// Schedule `shared`'s backing allocation to be freed once no thread
+    /// could still be reading it.
+    ///
+    /// # Safety
+    ///
+    /// `shared` must have just been unlinked from the structure it came
+    /// from, and must not be unlinked (or freed) again.
+    pub unsafe fn unlinked<T: Send + 'static>(&self, shared: Shared<T>) {
+        let raw = shared.as_raw() as usize;
+        GARBAGE.lock().unwrap().push(Box::new(move || {
+            drop(Box::from_raw(raw as *mut T));
+        }));
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if PINNED.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // We were the last pinned thread; it's safe to reclaim
+            // everything that has been unlinked so far.
+            let mut garbage = GARBAGE.lock().unwrap();
+            for free in garbage.drain(..) {
+                free();
+            }
+        }
+    }
+}
+
+/// Pin the current thread, returning a guard that permits reading from
+/// epoch-protected data and deferring frees until it is safe.
+pub fn pin() -> Guard {
+    PINNED.fetch_add(1, Ordering::SeqCst);
+    Guard { _private: () }
+}
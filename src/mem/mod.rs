@@ -0,0 +1,3 @@
+//! Memory-management facilities for lock-free data structures.
+
+pub mod epoch;
@@ -0,0 +1,267 @@
+//! A reusable pool of scoped worker threads.
+//!
+//! `scope` (see the `scoped` module) spawns a fresh OS thread for every
+//! task, which is wasteful for code that repeatedly runs short parallel
+//! sections — a benchmark loop, say. `Pool` keeps a fixed set of worker
+//! threads alive across many `scoped` calls, submitting borrow-capturing
+//! closures to them through `sync::ArrayQueue`, while still giving the
+//! same guarantee as `scope`: no task outlives the `scoped` call that
+//! spawned it.
+
+use std::marker::PhantomData;
+use std::mem;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Thread};
+
+use sync::ArrayQueue;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// The size of the internal task queue. Submitting more than this many
+/// outstanding tasks to a single `scoped` call just means `spawn` spins a
+/// little longer waiting for a worker to free up a slot.
+const QUEUE_CAPACITY: usize = 256;
+
+/// A fixed-size pool of worker threads that can repeatedly run scoped,
+/// borrow-capturing tasks without paying thread-creation cost each time.
+pub struct Pool {
+    tasks: Arc<ArrayQueue<Task>>,
+    // Workers parked in `worker_loop` with nothing to do, woken by the
+    // next `PoolScope::spawn` or by `Pool`'s own shutdown.
+    idle: Arc<Mutex<Vec<Thread>>>,
+    shutdown: Arc<AtomicBool>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+/// A handle to one `Pool::scoped` call, through which tasks are submitted.
+pub struct PoolScope<'pool, 'env> {
+    pool: &'pool Pool,
+    // Counts tasks submitted through *this* scope specifically (as opposed
+    // to a pool-wide count), so that one `scoped` call never waits on
+    // another concurrent `scoped` call's tasks.
+    outstanding: Arc<(Mutex<usize>, Condvar)>,
+    _marker: PhantomData<&'env ()>,
+}
+
+impl Pool {
+    /// Spin up a pool of `n` worker threads.
+    pub fn new(n: usize) -> Pool {
+        let tasks = Arc::new(ArrayQueue::with_capacity(QUEUE_CAPACITY));
+        let idle = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..n).map(|_| {
+            let tasks = tasks.clone();
+            let idle = idle.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || worker_loop(tasks, idle, shutdown))
+        }).collect();
+
+        Pool { tasks, idle, shutdown, workers }
+    }
+
+    /// Run `op` with a handle to a scope backed by this pool's workers.
+    ///
+    /// Blocks until every task submitted through *this* scope has
+    /// completed, the same guarantee `scope` gives for its spawned threads
+    /// — even if `op` panics, since `PoolScope`'s `Drop` waits out any
+    /// in-flight tasks while unwinding. Other concurrent `scoped` calls on
+    /// the same `Pool` are unaffected, since each scope tracks its own
+    /// tasks rather than sharing a pool-wide count.
+    pub fn scoped<'pool, 'env, F, R>(&'pool self, op: F) -> R
+        where F: FnOnce(&PoolScope<'pool, 'env>) -> R + Send
+    {
+        let scope = PoolScope {
+            pool: self,
+            outstanding: Arc::new((Mutex::new(0usize), Condvar::new())),
+            _marker: PhantomData,
+        };
+        op(&scope)
+    }
+}
+
+impl<'pool, 'env> PoolScope<'pool, 'env> {
+    /// Submit `f` to run on one of the pool's worker threads.
+    ///
+    /// `f` may borrow any data that outlives the scope `'env`; the
+    /// enclosing `Pool::scoped` call will not return until `f` has run.
+    pub fn spawn<F>(&self, f: F)
+        where F: FnOnce() + Send + 'env
+    {
+        {
+            let (lock, _) = &*self.outstanding;
+            *lock.lock().unwrap() += 1;
+        }
+
+        // `f` is wrapped so that whichever worker thread runs it also
+        // catches its panics and reports completion back to *this scope's*
+        // counter, rather than a pool-wide one — the worker loop just runs
+        // whatever task it's handed.
+        let outstanding = self.outstanding.clone();
+        let task: Box<dyn FnOnce() + Send + 'env> = Box::new(move || {
+            let _ = panic::catch_unwind(panic::AssertUnwindSafe(f));
+
+            let (lock, cvar) = &*outstanding;
+            let mut count = lock.lock().unwrap();
+            *count -= 1;
+            if *count == 0 {
+                cvar.notify_all();
+            }
+        });
+
+        // Same unsafe-transmute trick `scope` uses: `f` only needs to
+        // outlive `'env`, but the task queue demands `'static`. This is
+        // sound because `Pool::scoped` waits for every submitted task to
+        // finish before it returns, so the borrow can't dangle.
+        let task: Task = unsafe { mem::transmute(task) };
+
+        let mut task = task;
+        loop {
+            match self.pool.tasks.push(task) {
+                Ok(()) => break,
+                Err(rejected) => {
+                    task = rejected;
+                    thread::yield_now();
+                }
+            }
+        }
+
+        for worker in self.pool.idle.lock().unwrap().drain(..) {
+            worker.unpark();
+        }
+    }
+}
+
+impl<'pool, 'env> Drop for PoolScope<'pool, 'env> {
+    // Waits out every task submitted through this scope, even if `op`
+    // panicked and we're unwinding — otherwise a panicking `op` would
+    // return (via the panic) while tasks are still running against
+    // 'env-borrowed data.
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.outstanding;
+        let mut count = lock.lock().unwrap();
+        while *count != 0 {
+            count = cvar.wait(count).unwrap();
+        }
+    }
+}
+
+fn worker_loop(
+    tasks: Arc<ArrayQueue<Task>>,
+    idle: Arc<Mutex<Vec<Thread>>>,
+    shutdown: Arc<AtomicBool>,
+) {
+    loop {
+        if let Some(task) = tasks.pop() {
+            task();
+            continue;
+        }
+
+        // Register before re-checking so a `spawn` that lands between our
+        // failed `pop` above and here still finds us and unparks us,
+        // avoiding the lost-wakeup race.
+        idle.lock().unwrap().push(thread::current());
+        if let Some(task) = tasks.pop() {
+            task();
+            continue;
+        }
+
+        if shutdown.load(Ordering::Acquire) {
+            return;
+        }
+        thread::park();
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        for worker in self.idle.lock().unwrap().drain(..) {
+            worker.unpark();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pool;
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn scoped_tasks_complete_before_scoped_returns() {
+        let pool = Pool::new(4);
+        let count = AtomicUsize::new(0);
+
+        pool.scoped(|scope| {
+            for _ in 0..100 {
+                scope.spawn(|| { count.fetch_add(1, Ordering::SeqCst); });
+            }
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn pool_is_reused_across_scoped_calls() {
+        let pool = Pool::new(2);
+
+        for _ in 0..10 {
+            let count = AtomicUsize::new(0);
+            pool.scoped(|scope| {
+                for _ in 0..8 {
+                    scope.spawn(|| { count.fetch_add(1, Ordering::SeqCst); });
+                }
+            });
+            assert_eq!(count.load(Ordering::SeqCst), 8);
+        }
+    }
+
+    #[test]
+    fn waits_out_in_flight_tasks_even_if_op_panics() {
+        let pool = Pool::new(2);
+        let flag = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            pool.scoped(|scope| {
+                scope.spawn(|| {
+                    ::std::thread::sleep(Duration::from_millis(50));
+                    flag.store(1, Ordering::SeqCst);
+                });
+                panic!("boom");
+            });
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(flag.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn concurrent_scopes_do_not_block_on_each_others_tasks() {
+        let pool = Pool::new(4);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                pool.scoped(|s| {
+                    s.spawn(|| thread::sleep(Duration::from_millis(200)));
+                });
+            });
+
+            // Give the other scope's task time to be submitted and start
+            // running before this scope's empty `scoped` call checks how
+            // long it took to return.
+            thread::sleep(Duration::from_millis(20));
+
+            let start = Instant::now();
+            pool.scoped(|_| {});
+            assert!(start.elapsed() < Duration::from_millis(100));
+        });
+    }
+}
@@ -0,0 +1,182 @@
+//! Scoped thread spawning.
+//!
+//! This module provides an API for spawning threads that are statically
+//! guaranteed to be joined before the enclosing scope exits, which makes it
+//! sound for them to borrow data that lives on the stack of the scope's
+//! caller.
+
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A handle to a scope in which scoped threads can be spawned.
+pub struct Scope<'env> {
+    joins: Mutex<Vec<thread::JoinHandle<()>>>,
+    _marker: PhantomData<&'env ()>,
+}
+
+/// A handle to a scoped thread, returned by `Scope::spawn`.
+pub struct ScopedJoinHandle<T> {
+    result: Arc<Mutex<Option<thread::Result<T>>>>,
+}
+
+impl<'env> Scope<'env> {
+    fn new() -> Scope<'env> {
+        Scope { joins: Mutex::new(Vec::new()), _marker: PhantomData }
+    }
+
+    /// Spawn a scoped thread that runs `f`.
+    ///
+    /// `f` may borrow any data that outlives the scope `'env`, including
+    /// data that lives on the stack of the thread that called `scope`. The
+    /// returned handle can be used to retrieve `f`'s return value; if it is
+    /// dropped without being joined explicitly, `scope` will join it anyway
+    /// before returning.
+    pub fn spawn<'scope, F, T>(&'scope self, f: F) -> ScopedJoinHandle<T>
+        where F: FnOnce() -> T + Send + 'env, T: Send + 'env
+    {
+        let result = Arc::new(Mutex::new(None));
+        let their_result = result.clone();
+
+        // The closure captures borrows that are only valid for 'env, but
+        // `thread::spawn` demands `'static`. This is sound because `scope`
+        // blocks until every spawned thread has been joined before it
+        // returns, so none of the borrows can outlive the data they point
+        // to.
+        let f: Box<dyn FnOnce() + Send + 'env> = Box::new(move || {
+            let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(f));
+            *their_result.lock().unwrap() = Some(result);
+        });
+        let f: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(f) };
+
+        let join = thread::spawn(f);
+        self.joins.lock().unwrap().push(join);
+
+        ScopedJoinHandle { result }
+    }
+}
+
+impl<'env> Drop for Scope<'env> {
+    // Joins every still-outstanding thread, including when we're running
+    // because `op` panicked and we're unwinding out of `scope`. Without
+    // this, a panicking `op` would skip straight past an explicit join
+    // loop in `scope` and return (via the panic) while spawned threads
+    // are still running and touching borrowed `'env` data.
+    fn drop(&mut self) {
+        let mut joins = self.joins.lock().unwrap();
+        for join in joins.drain(..) {
+            let _ = join.join();
+        }
+    }
+}
+
+impl<T> ScopedJoinHandle<T> {
+    /// Join the scoped thread, returning its result.
+    ///
+    /// Panics if the thread panicked.
+    pub fn join(self) -> T {
+        loop {
+            if let Some(result) = self.result.lock().unwrap().take() {
+                return result.unwrap();
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+/// Create a new scope and invoke `op` with a handle to it.
+///
+/// All threads spawned through the scope's handle are joined before `scope`
+/// returns, so `op` and the threads it spawns may freely borrow data that
+/// outlives the scope. This holds even if `op` panics: `Scope`'s `Drop`
+/// joins any still-outstanding threads while unwinding, so a panicking
+/// `op` can never let a spawned thread outlive the borrows it captured.
+///
+/// Note that `'env` only constrains what *spawned* closures may borrow
+/// (via `Scope::spawn`'s own `F: 'env` bound); it is not required of `op`
+/// itself. `op` is called and fully returns (or unwinds) before `scope`
+/// does, so whatever `op` captures only needs to outlive `op`'s own call,
+/// which can be a shorter-lived borrow than anything handed to
+/// `scope.spawn`.
+///
+/// `op` is always called synchronously on the caller's own thread, so
+/// unlike `Scope::spawn`'s `f`, it has no need of a `Send` bound; a
+/// closure that captures an `Rc` or other `!Send` data is fine here.
+pub fn scope<'env, F, R>(op: F) -> R
+    where F: FnOnce(&Scope<'env>) -> R
+{
+    let scope = Scope::new();
+    op(&scope)
+}
+
+#[cfg(test)]
+mod test {
+    use super::scope;
+    use std::panic;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn body_capture_lifetime_independent_of_spawn_capture_lifetime() {
+        let long_lived = 7;
+
+        // `short_lived` doesn't escape this block, so the body closure
+        // passed to `scope` only needs to be valid for its own call, not
+        // for `'env` (which here is tied to `long_lived`, captured by the
+        // spawned thread instead).
+        let result = {
+            let short_lived = String::from("hi");
+            scope(|scope| {
+                let handle = scope.spawn(|| long_lived * 2);
+                short_lived.len() + handle.join()
+            })
+        };
+
+        assert_eq!(result, 2 + 14);
+    }
+
+    #[test]
+    fn spawned_threads_are_joined_before_scope_returns() {
+        let count = AtomicUsize::new(0);
+
+        scope(|scope| {
+            for _ in 0..10 {
+                scope.spawn(|| { count.fetch_add(1, Ordering::SeqCst); });
+            }
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn joins_outstanding_threads_even_if_op_panics() {
+        let flag = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            scope(|scope| {
+                scope.spawn(|| {
+                    thread::sleep(Duration::from_millis(50));
+                    flag.store(1, Ordering::SeqCst);
+                });
+                panic!("boom");
+            });
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(flag.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn op_may_capture_non_send_data() {
+        // `op` runs synchronously on the caller's thread, so it has no
+        // need of `Send`, unlike closures passed to `scope.spawn`.
+        let shared = Rc::new(7);
+
+        let result = scope(|_scope| *shared + 1);
+
+        assert_eq!(result, 8);
+    }
+}
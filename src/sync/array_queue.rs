@@ -0,0 +1,195 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Cell<T> {
+    sequence: AtomicUsize,
+    data: UnsafeCell<Option<T>>,
+}
+
+/// A bounded, array-backed, lock-free multi-producer/multi-consumer queue.
+///
+/// This is Dmitry Vyukov's bounded MPMC queue: a ring of cells, each
+/// carrying its own sequence number, so producers and consumers only ever
+/// contend on a single cell at a time rather than the whole buffer. Unlike
+/// `MsQueue`, capacity is fixed up front and pushing past it fails rather
+/// than growing, which means no per-element allocation and a predictable
+/// memory footprint — a good fit for fixed-size work queues.
+pub struct ArrayQueue<T> {
+    buffer: Vec<Cell<T>>,
+    mask: usize,
+    enqueue_pos: AtomicUsize,
+    dequeue_pos: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Create a queue that holds at least `capacity` elements.
+    ///
+    /// The actual capacity is rounded up to the next power of two so that
+    /// indexing into the buffer can use a bitmask instead of a modulo.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn with_capacity(capacity: usize) -> ArrayQueue<T> {
+        assert!(capacity > 0, "capacity must be non-zero");
+        let capacity = capacity.next_power_of_two();
+
+        let buffer = (0..capacity).map(|i| {
+            Cell { sequence: AtomicUsize::new(i), data: UnsafeCell::new(None) }
+        }).collect();
+
+        ArrayQueue {
+            buffer,
+            mask: capacity - 1,
+            enqueue_pos: AtomicUsize::new(0),
+            dequeue_pos: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of slots the queue can hold (a power of two, possibly
+    /// larger than what was requested in `with_capacity`).
+    pub fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Push `value` onto the queue, returning it back if the queue is
+    /// full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mask = self.mask;
+        let mut pos = self.enqueue_pos.load(Ordering::Relaxed);
+        loop {
+            let cell = &self.buffer[pos & mask];
+            let seq = cell.sequence.load(Ordering::Acquire);
+            let diff = seq as isize - pos as isize;
+
+            if diff == 0 {
+                match self.enqueue_pos.compare_exchange_weak(
+                    pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        unsafe { *cell.data.get() = Some(value) };
+                        cell.sequence.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(cur) => pos = cur,
+                }
+            } else if diff < 0 {
+                return Err(value);
+            } else {
+                pos = self.enqueue_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Push `value` onto the queue, evicting and returning the oldest
+    /// element if the queue is full rather than rejecting `value`.
+    ///
+    /// This turns the queue into an overwriting ring buffer, useful for
+    /// telemetry or "latest sample" workloads where a slow consumer
+    /// should lose old data rather than block a producer. Returns
+    /// `Some(old)` only when an element was actually displaced; `None`
+    /// when `value` fit without evicting anything.
+    pub fn force_push(&self, value: T) -> Option<T> {
+        let mut value = value;
+        let mut evicted = None;
+        loop {
+            match self.push(value) {
+                Ok(()) => return evicted,
+                Err(rejected) => {
+                    value = rejected;
+                    if evicted.is_none() {
+                        evicted = self.pop();
+                    }
+                    // Either we just evicted the oldest element, or we
+                    // lost the race with another consumer that popped the
+                    // slot we were about to evict; either way, retry the
+                    // push with `value` before reporting anything.
+                }
+            }
+        }
+    }
+
+    /// Pop the oldest element, returning `None` if the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        let mask = self.mask;
+        let mut pos = self.dequeue_pos.load(Ordering::Relaxed);
+        loop {
+            let cell = &self.buffer[pos & mask];
+            let seq = cell.sequence.load(Ordering::Acquire);
+            let diff = seq as isize - (pos + 1) as isize;
+
+            if diff == 0 {
+                match self.dequeue_pos.compare_exchange_weak(
+                    pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        let data = unsafe { (*cell.data.get()).take() };
+                        cell.sequence.store(pos + mask + 1, Ordering::Release);
+                        return data;
+                    }
+                    Err(cur) => pos = cur,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.dequeue_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ArrayQueue;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn force_push_inserts_value_after_evicting() {
+        let q = ArrayQueue::with_capacity(2);
+        q.push(1).unwrap();
+        q.push(2).unwrap();
+
+        assert_eq!(q.force_push(3), Some(1));
+
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn force_push_spsc_ring_buffer() {
+        // force_push is lossy by design, so a draining consumer is not
+        // guaranteed to observe every produced index, only each index at
+        // most once. Run the producer to completion, then drain whatever
+        // is left in the queue.
+        const COUNT: u64 = 100_000;
+
+        let q = ArrayQueue::with_capacity(3);
+        let producer_done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for x in 0..COUNT {
+                    q.force_push(x);
+                }
+                producer_done.store(true, Ordering::Release);
+            });
+
+            let mut seen = vec![false; COUNT as usize];
+            loop {
+                match q.pop() {
+                    Some(x) => {
+                        assert!(!seen[x as usize], "index {} observed twice", x);
+                        seen[x as usize] = true;
+                    }
+                    None if producer_done.load(Ordering::Acquire) => break,
+                    None => thread::yield_now(),
+                }
+            }
+        });
+    }
+}
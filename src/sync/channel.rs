@@ -0,0 +1,564 @@
+//! A bounded, multi-producer/single-consumer channel with backpressure.
+//!
+//! `std::sync::mpsc::channel` is unbounded, so a fast producer can run
+//! arbitrarily far ahead of a slow consumer. This channel is backed by
+//! `ArrayQueue`, so `send` blocks (or fails, via `try_send`) once
+//! `capacity` elements are buffered, giving producers real flow control.
+//! A `capacity` of 0 gives a single-slot channel: `send` waits until the
+//! slot is empty, stores the value and returns; it does not wait for a
+//! `recv` to take the value.
+//!
+//! Like `ArrayQueue`, the effective capacity is rounded up to the next
+//! power of two, so e.g. `channel(3)` actually buffers up to 4 elements
+//! before `send` blocks.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use sync::ArrayQueue;
+
+/// The sending half of a bounded channel, returned by `channel`.
+///
+/// Clone it to share it between multiple producer threads.
+pub struct Sender<T> {
+    inner: Arc<Channel<T>>,
+}
+
+/// The receiving half of a bounded channel, returned by `channel`.
+pub struct Receiver<T> {
+    inner: Arc<Channel<T>>,
+}
+
+enum Buffer<T> {
+    Bounded {
+        queue: ArrayQueue<T>,
+        send_waiters: Mutex<Vec<Thread>>,
+        recv_waiters: Mutex<Vec<Thread>>,
+    },
+    Rendezvous {
+        slot: Mutex<Option<T>>,
+        not_empty: Condvar,
+        not_full: Condvar,
+    },
+}
+
+struct Channel<T> {
+    buffer: Buffer<T>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+/// Create a bounded channel. `capacity` of 0 gives a single slot: `send`
+/// returns once the value is stored there, not once a `recv` takes it.
+///
+/// For `capacity > 0`, the channel is backed by an `ArrayQueue`, so the
+/// effective capacity is rounded up to the next power of two (see
+/// `ArrayQueue::with_capacity`) — `send`/`try_send` may accept a few more
+/// elements than `capacity` before blocking or failing.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let buffer = if capacity == 0 {
+        Buffer::Rendezvous {
+            slot: Mutex::new(None),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    } else {
+        Buffer::Bounded {
+            queue: ArrayQueue::with_capacity(capacity),
+            send_waiters: Mutex::new(Vec::new()),
+            recv_waiters: Mutex::new(Vec::new()),
+        }
+    };
+
+    let inner = Arc::new(Channel {
+        buffer,
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+/// Error returned by `Sender::send` when no `Receiver` remains to take the
+/// value.
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+/// Error returned by `Sender::try_send`.
+pub enum TrySendError<T> {
+    /// The channel is at capacity; no data was sent.
+    Full(T),
+    /// No `Receiver` remains to take the value.
+    Disconnected(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrySendError::Full(..) => f.write_str("Full(..)"),
+            TrySendError::Disconnected(..) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+/// Error returned by `Sender::send_timeout`.
+pub enum SendTimeoutError<T> {
+    /// The channel stayed at capacity for the whole timeout.
+    Timeout(T),
+    /// No `Receiver` remains to take the value.
+    Disconnected(T),
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SendTimeoutError::Timeout(..) => f.write_str("Timeout(..)"),
+            SendTimeoutError::Disconnected(..) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+/// Error returned by `Receiver::recv` when the channel is empty and every
+/// `Sender` has been dropped.
+#[derive(Debug)]
+pub struct RecvError;
+
+/// Error returned by `Receiver::try_recv`.
+#[derive(Debug)]
+pub enum TryRecvError {
+    /// The channel is empty, but at least one `Sender` is still alive.
+    Empty,
+    /// The channel is empty and every `Sender` has been dropped.
+    Disconnected,
+}
+
+/// Error returned by `Receiver::recv_timeout`.
+#[derive(Debug)]
+pub enum RecvTimeoutError {
+    /// The channel stayed empty for the whole timeout.
+    Timeout,
+    /// The channel is empty and every `Sender` has been dropped.
+    Disconnected,
+}
+
+impl<T> Sender<T> {
+    /// Send `t`, blocking until there is room (for a zero-capacity
+    /// channel, until the single slot is empty; the call does not wait for
+    /// a `recv` to take `t`).
+    ///
+    /// Fails if every `Receiver` has been dropped.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        match self.inner.send(t, None) {
+            Ok(()) => Ok(()),
+            Err(SendTimeoutError::Disconnected(t)) => Err(SendError(t)),
+            Err(SendTimeoutError::Timeout(_)) => unreachable!("send has no deadline"),
+        }
+    }
+
+    /// Send `t` if there is room right now, without blocking.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(t)
+    }
+
+    /// Send `t`, blocking for at most `dur` for room to appear.
+    pub fn send_timeout(&self, t: T, dur: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.inner.send(t, Some(Instant::now() + dur))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.senders.fetch_add(1, Ordering::Relaxed);
+        Sender { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.wake_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receive a value, blocking until one is available.
+    ///
+    /// Fails if the channel is empty and every `Sender` has been dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv(None) {
+            Ok(t) => Ok(t),
+            Err(RecvTimeoutError::Disconnected) => Err(RecvError),
+            Err(RecvTimeoutError::Timeout) => unreachable!("recv has no deadline"),
+        }
+    }
+
+    /// Receive a value if one is available right now, without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// Receive a value, blocking for at most `dur`.
+    pub fn recv_timeout(&self, dur: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Some(Instant::now() + dur))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_alive.store(false, Ordering::Release);
+        self.inner.wake_all();
+    }
+}
+
+impl<T> Channel<T> {
+    fn wake_all(&self) {
+        match self.buffer {
+            Buffer::Bounded { ref send_waiters, ref recv_waiters, .. } => {
+                for t in send_waiters.lock().unwrap().drain(..) { t.unpark(); }
+                for t in recv_waiters.lock().unwrap().drain(..) { t.unpark(); }
+            }
+            Buffer::Rendezvous { ref slot, ref not_empty, ref not_full } => {
+                let _slot = slot.lock().unwrap();
+                not_empty.notify_all();
+                not_full.notify_all();
+            }
+        }
+    }
+
+    fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        if !self.receiver_alive.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(t));
+        }
+        match self.buffer {
+            Buffer::Bounded { ref queue, ref recv_waiters, .. } => {
+                match queue.push(t) {
+                    Ok(()) => {
+                        for t in recv_waiters.lock().unwrap().drain(..) { t.unpark(); }
+                        Ok(())
+                    }
+                    Err(t) => Err(TrySendError::Full(t)),
+                }
+            }
+            Buffer::Rendezvous { ref slot, ref not_empty, .. } => {
+                let mut slot = slot.lock().unwrap();
+                if slot.is_some() {
+                    return Err(TrySendError::Full(t));
+                }
+                *slot = Some(t);
+                not_empty.notify_one();
+                Ok(())
+            }
+        }
+    }
+
+    fn send(&self, mut t: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        // We register in `send_waiters` before every park below (needed so
+        // a slot freed between the failed `try_send` and registering isn't
+        // a lost wakeup), but `ensure_registered` is a no-op if we're
+        // already listed, so a thread that parks and retries repeatedly
+        // over one `send` call still only ever occupies one slot in the
+        // `Vec` — not one per retry. We *can* still be silently dropped
+        // from the list by an unrelated successful `recv` (it drains
+        // everyone to avoid missing whoever the freed slot was actually
+        // for), so `registered` only means "we might still be listed",
+        // not "we are"; `ensure_registered` re-adds us in that case too.
+        let mut registered = false;
+
+        let result = loop {
+            match self.try_send(t) {
+                Ok(()) => break Ok(()),
+                Err(TrySendError::Disconnected(rejected)) => {
+                    break Err(SendTimeoutError::Disconnected(rejected));
+                }
+                Err(TrySendError::Full(rejected)) => t = rejected,
+            }
+
+            match self.buffer {
+                Buffer::Bounded { ref send_waiters, .. } => {
+                    ensure_registered(send_waiters);
+                    registered = true;
+                    // Recheck right after (re-)registering: a slot may
+                    // have freed up, and whoever freed it drains and wakes
+                    // the whole waiter list, which only reaches threads
+                    // already registered at that moment.
+                    match self.try_send(t) {
+                        Ok(()) => break Ok(()),
+                        Err(TrySendError::Disconnected(rejected)) => {
+                            break Err(SendTimeoutError::Disconnected(rejected));
+                        }
+                        Err(TrySendError::Full(rejected)) => t = rejected,
+                    }
+                    if !park_until(deadline) {
+                        break Err(SendTimeoutError::Timeout(t));
+                    }
+                }
+                Buffer::Rendezvous { ref slot, ref not_full, .. } => {
+                    let mut guard = slot.lock().unwrap();
+                    while guard.is_some() {
+                        if !self.receiver_alive.load(Ordering::Acquire) {
+                            return Err(SendTimeoutError::Disconnected(t));
+                        }
+                        guard = match wait_until(not_full, guard, deadline) {
+                            Some(guard) => guard,
+                            None => return Err(SendTimeoutError::Timeout(t)),
+                        };
+                    }
+                    // Fall through to retry `try_send`, which will claim
+                    // the now-empty slot (or observe disconnection).
+                }
+            }
+        };
+
+        if registered {
+            if let Buffer::Bounded { ref send_waiters, .. } = self.buffer {
+                deregister(send_waiters);
+            }
+        }
+
+        result
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.buffer {
+            Buffer::Bounded { ref queue, ref send_waiters, .. } => {
+                match queue.pop() {
+                    Some(t) => {
+                        for t in send_waiters.lock().unwrap().drain(..) { t.unpark(); }
+                        Ok(t)
+                    }
+                    None if self.senders.load(Ordering::Acquire) == 0 => Err(TryRecvError::Disconnected),
+                    None => Err(TryRecvError::Empty),
+                }
+            }
+            Buffer::Rendezvous { ref slot, ref not_full, .. } => {
+                let mut slot = slot.lock().unwrap();
+                match slot.take() {
+                    Some(t) => { not_full.notify_one(); Ok(t) }
+                    None if self.senders.load(Ordering::Acquire) == 0 => Err(TryRecvError::Disconnected),
+                    None => Err(TryRecvError::Empty),
+                }
+            }
+        }
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        // See the matching comment in `send`: we re-register (cheaply, via
+        // a no-op `ensure_registered`) before every park, since a `send`'s
+        // drain-and-wake can silently drop us from the list, and we always
+        // deregister on the way out rather than relying on some later
+        // `send` to drain the whole `Vec`.
+        let mut registered = false;
+
+        let result = loop {
+            match self.try_recv() {
+                Ok(t) => break Ok(t),
+                Err(TryRecvError::Disconnected) => break Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => (),
+            }
+
+            match self.buffer {
+                Buffer::Bounded { ref recv_waiters, .. } => {
+                    // See the matching comment in `send` on why this
+                    // re-registers (cheaply, via a no-op `ensure_registered`
+                    // when already listed) and rechecks every iteration
+                    // rather than just once.
+                    ensure_registered(recv_waiters);
+                    registered = true;
+                    match self.try_recv() {
+                        Ok(t) => break Ok(t),
+                        Err(TryRecvError::Disconnected) => break Err(RecvTimeoutError::Disconnected),
+                        Err(TryRecvError::Empty) => (),
+                    }
+                    if !park_until(deadline) {
+                        break Err(RecvTimeoutError::Timeout);
+                    }
+                }
+                Buffer::Rendezvous { ref slot, ref not_empty, .. } => {
+                    let mut guard = slot.lock().unwrap();
+                    while guard.is_none() {
+                        if self.senders.load(Ordering::Acquire) == 0 {
+                            return Err(RecvTimeoutError::Disconnected);
+                        }
+                        guard = match wait_until(not_empty, guard, deadline) {
+                            Some(guard) => guard,
+                            None => return Err(RecvTimeoutError::Timeout),
+                        };
+                    }
+                    // Fall through to retry `try_recv`, which will take
+                    // the now-full slot.
+                }
+            }
+        };
+
+        if registered {
+            if let Buffer::Bounded { ref recv_waiters, .. } = self.buffer {
+                deregister(recv_waiters);
+            }
+        }
+
+        result
+    }
+}
+
+/// Add the current thread to `waiters`, unless it's already listed.
+///
+/// Callers re-register on every retry, since a successful `push`/`pop`
+/// drains and wakes the *entire* waiter list (it can't know which of
+/// several blocked threads the freed slot was "for"), which silently
+/// drops every other registered thread, including ones that lost the
+/// race for that slot and are still waiting. The position check keeps a
+/// thread that parks and retries repeatedly to only ever occupy one slot
+/// in the `Vec`, rather than growing it once per retry.
+fn ensure_registered(waiters: &Mutex<Vec<Thread>>) {
+    let id = thread::current().id();
+    let mut waiters = waiters.lock().unwrap();
+    if !waiters.iter().any(|t| t.id() == id) {
+        waiters.push(thread::current());
+    }
+}
+
+/// Remove the current thread's entry from `waiters`, if still present.
+///
+/// Called once a blocking `send`/`recv` call is about to return, so a
+/// thread that gave up (succeeded, timed out, or saw disconnection)
+/// doesn't linger in the list waiting for some unrelated future
+/// `push`/`pop` to drain it away.
+fn deregister(waiters: &Mutex<Vec<Thread>>) {
+    let id = thread::current().id();
+    let mut waiters = waiters.lock().unwrap();
+    if let Some(pos) = waiters.iter().position(|t| t.id() == id) {
+        waiters.swap_remove(pos);
+    }
+}
+
+/// Park until `deadline`, or forever if `None`. Returns `false` on
+/// timeout.
+fn park_until(deadline: Option<Instant>) -> bool {
+    match deadline {
+        None => { thread::park(); true }
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline { return false; }
+            thread::park_timeout(deadline - now);
+            Instant::now() < deadline
+        }
+    }
+}
+
+/// Wait on `cvar` until `deadline`, or forever if `None`. Returns `None`
+/// on timeout.
+fn wait_until<'a, T>(
+    cvar: &Condvar,
+    guard: ::std::sync::MutexGuard<'a, T>,
+    deadline: Option<Instant>,
+) -> Option<::std::sync::MutexGuard<'a, T>> {
+    match deadline {
+        None => Some(cvar.wait(guard).unwrap()),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline { return None; }
+            let (guard, timeout) = cvar.wait_timeout(guard, deadline - now).unwrap();
+            if timeout.timed_out() { None } else { Some(guard) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn try_send_error_is_debuggable() {
+        let (tx, _rx) = channel(2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        // `unwrap_err` (like `unwrap`) requires `Debug`, so this wouldn't
+        // compile if `TrySendError` weren't `Debug`.
+        assert_eq!(format!("{:?}", tx.try_send(3).unwrap_err()), "Full(..)");
+    }
+
+    #[test]
+    fn capacity_is_rounded_up_to_a_power_of_two() {
+        // Documented on `channel`: the effective capacity matches
+        // `ArrayQueue`'s, so a requested capacity of 3 actually buffers 4.
+        let (tx, _rx) = channel(3);
+        for x in 0..4 {
+            tx.send(x).unwrap();
+        }
+        assert!(tx.try_send(4).is_err());
+    }
+
+    #[test]
+    fn bounded_send_recv_roundtrip() {
+        let (tx, rx) = channel(4);
+        for x in 0..4 {
+            tx.send(x).unwrap();
+        }
+        assert!(tx.try_send(4).is_err());
+        for x in 0..4 {
+            assert_eq!(rx.recv().unwrap(), x);
+        }
+    }
+
+    #[test]
+    fn rendezvous_send_blocks_until_recv() {
+        let (tx, rx) = channel(0);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                tx.send(42).unwrap();
+            });
+
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(rx.recv().unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn recv_fails_once_all_senders_dropped() {
+        let (tx, rx) = channel::<u32>(2);
+        drop(tx);
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn send_fails_once_receiver_dropped() {
+        let (tx, rx) = channel::<u32>(1);
+        drop(rx);
+        assert!(tx.send(1).is_err());
+    }
+
+    #[test]
+    fn mpsc_with_cloned_senders() {
+        let (tx, rx) = channel(8);
+        thread::scope(|scope| {
+            for i in 0..4 {
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        tx.send(i).unwrap();
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut received = 0;
+            while let Ok(_) = rx.recv() {
+                received += 1;
+                if received == 400 { break; }
+            }
+            assert_eq!(received, 400);
+        });
+    }
+}
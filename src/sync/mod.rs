@@ -0,0 +1,11 @@
+//! Concurrent data structures.
+
+pub use self::ms_queue::MsQueue;
+pub use self::array_queue::ArrayQueue;
+pub use self::channel::{channel, Sender, Receiver};
+pub use self::channel::{SendError, TrySendError, SendTimeoutError};
+pub use self::channel::{RecvError, TryRecvError, RecvTimeoutError};
+
+mod ms_queue;
+mod array_queue;
+mod channel;
@@ -0,0 +1,256 @@
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use mem::epoch::{self, Atomic, Owned};
+
+struct Node<T> {
+    data: Option<T>,
+    next: Atomic<Node<T>>,
+}
+
+/// A Michael-Scott lock-free, unbounded, multi-producer/multi-consumer
+/// queue.
+///
+/// Every push allocates a node; every pop frees one (after an epoch grace
+/// period), so this is a fine default but a poor fit for workloads that
+/// want to avoid per-element allocation — see `ArrayQueue` for that case.
+pub struct MsQueue<T: Send + 'static> {
+    head: Atomic<Node<T>>,
+    tail: Atomic<Node<T>>,
+    // Consumers parked in `pop_blocking`/`pop_timeout`, woken by the next
+    // `push`. Each waiter registers itself at most once (see
+    // `ensure_registered`) and removes its own entry once it stops waiting
+    // (see `deregister`), rather than lingering here until some unrelated
+    // future `push` drains the whole list.
+    waiters: Mutex<Vec<Thread>>,
+}
+
+unsafe impl<T: Send + 'static> Sync for MsQueue<T> {}
+
+impl<T: Send + 'static> Default for MsQueue<T> {
+    fn default() -> MsQueue<T> { MsQueue::new() }
+}
+
+impl<T: Send + 'static> MsQueue<T> {
+    /// Create a new, empty queue.
+    pub fn new() -> MsQueue<T> {
+        let q = MsQueue {
+            head: Atomic::null(),
+            tail: Atomic::null(),
+            waiters: Mutex::new(Vec::new()),
+        };
+        let sentinel = Owned::new(Node { data: None, next: Atomic::null() });
+        let guard = epoch::pin();
+        q.head.store(Some(sentinel), Relaxed);
+        let head = q.head.load(Relaxed, &guard).unwrap();
+        q.tail.store_shared(Some(head), Relaxed);
+        q
+    }
+
+    /// Push `t` onto the back of the queue.
+    pub fn push(&self, t: T) {
+        let mut new = Owned::new(Node { data: Some(t), next: Atomic::null() });
+        let guard = epoch::pin();
+        loop {
+            let tail = self.tail.load(Acquire, &guard).unwrap();
+            if let Some(next) = tail.next.load(Acquire, &guard) {
+                // Tail lagged behind; help move it forward and retry.
+                let _ = self.tail.cas_shared(Some(tail), Some(next), Release);
+                continue;
+            }
+            match tail.next.cas(None, Some(new), Release) {
+                Ok(()) => {
+                    let new_tail = self.tail.load(Acquire, &guard);
+                    let _ = self.tail.cas_shared(Some(tail), new_tail, Release);
+                    for waiter in self.waiters.lock().unwrap().drain(..) {
+                        waiter.unpark();
+                    }
+                    return;
+                }
+                Err(returned) => {
+                    new = returned.unwrap();
+                }
+            }
+        }
+    }
+
+    /// Attempt to pop the front of the queue, returning `None` if empty.
+    pub fn pop(&self) -> Option<T> {
+        let guard = epoch::pin();
+        loop {
+            let head = self.head.load(Acquire, &guard).unwrap();
+            let next = head.next.load(Acquire, &guard);
+            match next {
+                None => return None,
+                Some(next) => {
+                    if self.head.cas_shared(Some(head), Some(next), Release) {
+                        unsafe { guard.unlinked(head) };
+                        // `next` becomes the new sentinel; take its data,
+                        // leaving `None` behind so it isn't dropped again
+                        // when `next` itself is later retired.
+                        let data = unsafe { (*next.as_raw()).data.take() };
+                        return data;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pop the front of the queue, parking the calling thread until an
+    /// element is available rather than spinning.
+    pub fn pop_blocking(&self) -> T {
+        let mut registered = false;
+
+        let result = loop {
+            if let Some(t) = self.pop() {
+                break t;
+            }
+
+            // Register before re-checking so a push that lands between
+            // our failed `pop` above and here still finds us and unparks
+            // us; otherwise we could park with the element already
+            // waiting (the lost-wakeup race). A successful `push` drains
+            // and wakes the *entire* waiter list, which can silently clear
+            // our entry before our turn; `ensure_registered` is a no-op if
+            // we're already listed, so re-calling it every iteration
+            // re-adds us in that case too.
+            ensure_registered(&self.waiters);
+            registered = true;
+            if let Some(t) = self.pop() {
+                break t;
+            }
+
+            thread::park();
+        };
+
+        if registered {
+            deregister(&self.waiters);
+        }
+        result
+    }
+
+    /// Pop the front of the queue, parking the calling thread for up to
+    /// `dur` if it's empty. Returns `None` on timeout.
+    pub fn pop_timeout(&self, dur: Duration) -> Option<T> {
+        let deadline = Instant::now() + dur;
+        let mut registered = false;
+
+        let result = loop {
+            if let Some(t) = self.pop() {
+                break Some(t);
+            }
+
+            // See the matching comment in `pop_blocking`.
+            ensure_registered(&self.waiters);
+            registered = true;
+            if let Some(t) = self.pop() {
+                break Some(t);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break None;
+            }
+            thread::park_timeout(deadline - now);
+        };
+
+        if registered {
+            deregister(&self.waiters);
+        }
+        result
+    }
+}
+
+impl<T: Send + 'static> Drop for MsQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+
+        // `pop` only ever frees the nodes it unlinks from the front, so
+        // the sentinel node `head` still points at once the queue is
+        // empty is never unlinked, and would otherwise leak. We have
+        // exclusive access to `self` here, so it's safe to free it
+        // directly rather than going through the epoch GC.
+        let guard = epoch::pin();
+        if let Some(head) = self.head.load(Acquire, &guard) {
+            unsafe { drop(Box::from_raw(head.as_raw())) };
+        }
+    }
+}
+
+/// Add the current thread to `waiters`, unless it's already listed.
+///
+/// Callers re-register on every retry, since a successful `push` drains
+/// and wakes the *entire* waiter list (it can't know which of several
+/// blocked consumers the new element is "for"), which silently drops
+/// every other registered thread, including ones that are still waiting.
+/// The position check keeps a thread that parks and retries repeatedly to
+/// only ever occupy one slot in the `Vec`, rather than growing it once per
+/// retry.
+fn ensure_registered(waiters: &Mutex<Vec<Thread>>) {
+    let id = thread::current().id();
+    let mut waiters = waiters.lock().unwrap();
+    if !waiters.iter().any(|t| t.id() == id) {
+        waiters.push(thread::current());
+    }
+}
+
+/// Remove the current thread's entry from `waiters`, if still present.
+///
+/// Called once a blocking `pop_blocking`/`pop_timeout` call is about to
+/// return, so a thread that gave up (found an element or timed out)
+/// doesn't linger in the list waiting for some unrelated future `push` to
+/// drain it away.
+fn deregister(waiters: &Mutex<Vec<Thread>>) {
+    let id = thread::current().id();
+    let mut waiters = waiters.lock().unwrap();
+    if let Some(pos) = waiters.iter().position(|t| t.id() == id) {
+        waiters.swap_remove(pos);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MsQueue;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn pop_blocking_wakes_on_push() {
+        let q = MsQueue::new();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                q.push(42);
+            });
+
+            assert_eq!(q.pop_blocking(), 42);
+        });
+    }
+
+    #[test]
+    fn drop_frees_the_sentinel_node_of_an_empty_queue() {
+        // Exercises `Drop` on a queue that never had anything pushed to
+        // it, so `pop` never runs and the only node to reclaim is the
+        // initial sentinel itself.
+        let q: MsQueue<u32> = MsQueue::new();
+        drop(q);
+    }
+
+    #[test]
+    fn pop_timeout_expires_on_empty_queue() {
+        let q: MsQueue<u32> = MsQueue::new();
+        assert_eq!(q.pop_timeout(Duration::from_millis(20)), None);
+    }
+
+    #[test]
+    fn pop_timeout_does_not_leak_waiters_while_idle() {
+        let q: MsQueue<u32> = MsQueue::new();
+        for _ in 0..1000 {
+            assert_eq!(q.pop_timeout(Duration::from_millis(0)), None);
+        }
+        assert_eq!(q.waiters.lock().unwrap().len(), 0);
+    }
+}